@@ -1,22 +1,42 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{
+    Arc, Condvar, LockResult, Mutex, PoisonError, RwLock, TryLockError, TryLockResult, Weak,
+};
+use std::time::{Duration, Instant};
+
+/// A keyed lock entry together with whether a previous holder panicked
+/// while it was locked. `poisoned` lives here, shared with every
+/// [`KeyedLock`] built for this key, rather than on the `KeyedLock` itself
+/// so it survives eviction: once every `Arc` for a key is dropped,
+/// [`SyncLocker::get_mutex`] hands out a fresh `KeyedLock` for that key, but
+/// the key's poisoning should not be forgotten along with it.
+struct MutexEntry {
+    mutex: Weak<KeyedLock>,
+    poisoned: Arc<AtomicBool>,
+}
 
 #[derive(Clone)]
 pub struct SyncLocker<K> {
-    mutexes: Arc<RwLock<HashMap<K, Arc<Mutex<()>>>>>,
+    mutexes: Arc<RwLock<HashMap<K, MutexEntry>>>,
 }
 
 impl<K: Eq + Hash> SyncLocker<K> {
     pub fn new() -> Self {
         SyncLocker {
-            mutexes: Arc::new(RwLock::new(HashMap::<K, Arc<Mutex<()>>>::new())),
+            mutexes: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    /// Return reference to existig `Mutex` or insert new one.
+    /// Return reference to existig lock or insert new one.
+    ///
+    /// Locks the current task until it is able to return the lock.
     ///
-    /// Locks the current task until it is able to return `Mutex`.
+    /// Only a `Weak` reference to the lock is kept in the map, so once every
+    /// `Arc` returned for a key is dropped the entry becomes dead and its memory
+    /// can be reclaimed with [`SyncLocker::retain_live`].
     ///
     /// # Examples
     /// ```ignore
@@ -34,26 +54,209 @@ impl<K: Eq + Hash> SyncLocker<K> {
     /// });
     /// thread::sleep(Duration::from_millis(200));
     /// ```
-    pub fn get_mutex(&self, key: K) -> Arc<Mutex<()>> {
+    pub fn get_mutex(&self, key: K) -> Arc<KeyedLock> {
         {
             let mutexes = self.mutexes.read().unwrap();
-            let mutex_opt = mutexes.get(&key);
-            if let Some(mutex) = mutex_opt {
-                return mutex.clone();
-            };
+            if let Some(mutex) = mutexes.get(&key).and_then(|entry| entry.mutex.upgrade()) {
+                return mutex;
+            }
         }
         let mut mutexes = self.mutexes.write().unwrap();
-        let new_mutex = Arc::new(Mutex::new(()));
-        mutexes.entry(key).or_insert(new_mutex).clone()
+        match mutexes.entry(key) {
+            Entry::Occupied(mut entry) => entry.get().mutex.upgrade().unwrap_or_else(|| {
+                let mutex = Arc::new(KeyedLock::new(entry.get().poisoned.clone()));
+                entry.get_mut().mutex = Arc::downgrade(&mutex);
+                mutex
+            }),
+            Entry::Vacant(entry) => {
+                let poisoned = Arc::new(AtomicBool::new(false));
+                let mutex = Arc::new(KeyedLock::new(poisoned.clone()));
+                entry.insert(MutexEntry {
+                    mutex: Arc::downgrade(&mutex),
+                    poisoned,
+                });
+                mutex
+            }
+        }
+    }
+
+    /// Drops entries whose lock no longer has any `Arc` holders, shrinking
+    /// the underlying map back down to the set of currently active keys.
+    /// This also forgets those keys' poisoning, same as dropping a
+    /// `std::sync::Mutex` forgets whether it was poisoned.
+    pub fn retain_live(&self) {
+        let mut mutexes = self.mutexes.write().unwrap();
+        mutexes.retain(|_, entry| entry.mutex.strong_count() > 0);
+    }
+
+    /// Number of keys whose lock is still alive.
+    pub fn len(&self) -> usize {
+        let mutexes = self.mutexes.read().unwrap();
+        mutexes.values().filter(|entry| entry.mutex.strong_count() > 0).count()
+    }
+
+    /// Returns `true` if no key currently has a live lock.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Acquires `key`'s lock, runs `code` while holding it, and checks the
+    /// acquisition against the global lock-order graph so a cyclic lock order
+    /// is caught deterministically instead of only manifesting as a runtime
+    /// deadlock.
+    #[cfg(feature = "deadlock-detection")]
+    pub fn with_lock<F, T>(&self, key: K, code: F) -> T
+    where
+        K: Clone + std::fmt::Debug,
+        F: FnOnce() -> T,
+    {
+        let id = format!("{:?}", key);
+        let mutex = self.get_mutex(key);
+        let _lock_order_guard = crate::deadlock::before_lock(&id);
+        let _guard = mutex.lock().unwrap();
+        code()
+    }
+
+    /// Blocks the current thread until `key`'s lock is acquired or `timeout`
+    /// elapses, whichever comes first. Returns `None` on timeout.
+    ///
+    /// This acquires the very same per-key lock [`SyncLocker::get_mutex`]
+    /// returns, so a `lock_timeout` caller and a `get_mutex` caller exclude
+    /// each other for the same key. Where [`KeyedLock::lock`] reports
+    /// poisoning as an `Err`, this panics if a previous holder of the lock
+    /// panicked while holding it, as `lock().unwrap()` would.
+    pub fn lock_timeout(&self, key: K, timeout: Duration) -> Option<KeyedLockGuard> {
+        self.get_mutex(key).lock_timeout(timeout)
+    }
+}
+
+/// Per-key lock handed out by [`SyncLocker::get_mutex`]. Built on a
+/// `Condvar` rather than `std::sync::Mutex` so [`SyncLocker::lock_timeout`]
+/// can bound how long it waits to acquire it, but follows `std::sync::Mutex`'s
+/// poisoning strategy: a panic while a guard is held poisons the lock for
+/// every later acquirer.
+///
+/// `poisoned` is shared with [`SyncLocker`]'s map entry for this key rather
+/// than owned outright, so that poisoning is observed by every `KeyedLock`
+/// built for the key -- including ones created after this one was evicted --
+/// instead of being forgotten the moment this particular instance is dropped.
+pub struct KeyedLock {
+    state: Mutex<KeyedLockState>,
+    condvar: Condvar,
+    poisoned: Arc<AtomicBool>,
+}
+
+struct KeyedLockState {
+    locked: bool,
+}
+
+impl KeyedLock {
+    fn new(poisoned: Arc<AtomicBool>) -> Self {
+        KeyedLock {
+            state: Mutex::new(KeyedLockState { locked: false }),
+            condvar: Condvar::new(),
+            poisoned,
+        }
+    }
+
+    /// Blocks the current thread until the lock is acquired. Returns
+    /// `Err` if a previous holder panicked while holding it, same as
+    /// `std::sync::Mutex::lock`.
+    pub fn lock(self: &Arc<Self>) -> LockResult<KeyedLockGuard> {
+        let mut state = self.state.lock().unwrap();
+        while state.locked {
+            state = self.condvar.wait(state).unwrap();
+        }
+        state.locked = true;
+        drop(state);
+        let poisoned = self.poisoned.load(Ordering::Acquire);
+        let guard = KeyedLockGuard { lock: self.clone() };
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Acquires the lock without blocking, returning
+    /// `Err(TryLockError::WouldBlock)` if it is currently held.
+    pub fn try_lock(self: &Arc<Self>) -> TryLockResult<KeyedLockGuard> {
+        let mut state = self.state.lock().unwrap();
+        if state.locked {
+            return Err(TryLockError::WouldBlock);
+        }
+        state.locked = true;
+        drop(state);
+        let poisoned = self.poisoned.load(Ordering::Acquire);
+        let guard = KeyedLockGuard { lock: self.clone() };
+        if poisoned {
+            Err(TryLockError::Poisoned(PoisonError::new(guard)))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    fn lock_timeout(self: &Arc<Self>, timeout: Duration) -> Option<KeyedLockGuard> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        while state.locked {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            let (s, result) = self.condvar.wait_timeout(state, remaining).unwrap();
+            state = s;
+            if result.timed_out() && state.locked {
+                return None;
+            }
+        }
+        state.locked = true;
+        drop(state);
+        if self.poisoned.load(Ordering::Acquire) {
+            // No `Result` slot to report poisoning through here, so release
+            // what we just acquired and panic, matching what `lock().unwrap()`
+            // would do for the same poisoned lock.
+            self.unlock(false);
+            panic!("KeyedLock poisoned by a panicking holder");
+        }
+        Some(KeyedLockGuard { lock: self.clone() })
+    }
+
+    fn unlock(&self, poison: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.locked = false;
+        drop(state);
+        if poison {
+            self.poisoned.store(true, Ordering::Release);
+        }
+        self.condvar.notify_one();
+    }
+}
+
+/// Guard returned by [`KeyedLock::lock`], [`KeyedLock::try_lock`] and
+/// [`SyncLocker::lock_timeout`]. Releases the lock on drop, poisoning it if
+/// the thread is unwinding from a panic while the guard was held.
+pub struct KeyedLockGuard {
+    lock: Arc<KeyedLock>,
+}
+
+impl Drop for KeyedLockGuard {
+    fn drop(&mut self) {
+        self.lock.unlock(std::thread::panicking());
+    }
+}
+
+impl<K: Eq + Hash> Default for SyncLocker<K> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SyncLocker;
+    use std::sync::Arc;
     use std::thread;
     use std::time::Duration;
 
+    #[test]
     fn test_sync_locker() {
         let locker = SyncLocker::new();
         let locker_clone = locker.clone();
@@ -98,4 +301,87 @@ mod tests {
 
         handle.join().unwrap();
     }
+
+    #[test]
+    fn test_get_mutex_returns_same_instance_while_alive() {
+        let locker = SyncLocker::new();
+        let mutex = locker.get_mutex(1);
+        let other = locker.get_mutex(1);
+        assert!(Arc::ptr_eq(&mutex, &other));
+    }
+
+    #[test]
+    fn test_map_shrinks_after_guards_are_dropped() {
+        let locker = SyncLocker::new();
+        {
+            let _mutex = locker.get_mutex(1);
+            assert_eq!(locker.mutexes.read().unwrap().len(), 1);
+            assert_eq!(locker.len(), 1);
+        }
+        assert_eq!(locker.mutexes.read().unwrap().len(), 1);
+        assert_eq!(locker.len(), 0);
+
+        locker.retain_live();
+        assert_eq!(locker.mutexes.read().unwrap().len(), 0);
+        assert!(locker.is_empty());
+    }
+
+    #[test]
+    fn test_lock_timeout_succeeds_once_free() {
+        let locker = Arc::new(SyncLocker::new());
+        let locker_clone = locker.clone();
+        let held = locker.lock_timeout(1, Duration::from_secs(1)).unwrap();
+
+        let handle = thread::spawn(move || {
+            locker_clone.lock_timeout(1, Duration::from_secs(1)).is_some()
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        drop(held);
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_lock_timeout_returns_none_on_timeout() {
+        let locker = Arc::new(SyncLocker::new());
+        let _held = locker.lock_timeout(1, Duration::from_secs(1)).unwrap();
+        assert!(locker.lock_timeout(1, Duration::from_millis(50)).is_none());
+    }
+
+    #[test]
+    fn test_lock_timeout_excludes_get_mutex_holder() {
+        let locker = SyncLocker::new();
+        let mutex = locker.get_mutex(1);
+        let _guard = mutex.lock().unwrap();
+
+        assert!(locker.lock_timeout(1, Duration::from_millis(100)).is_none());
+    }
+
+    #[test]
+    fn test_get_mutex_excludes_lock_timeout_holder() {
+        let locker = SyncLocker::new();
+        let _held = locker.lock_timeout(1, Duration::from_secs(1)).unwrap();
+
+        let mutex = locker.get_mutex(1);
+        assert!(mutex.try_lock().is_err());
+    }
+
+    #[test]
+    fn test_lock_propagates_poison() {
+        // No `_keepalive` `Arc` is held here: the panicking thread drops its
+        // own `Arc` as soon as it exits, so this also proves that poisoning
+        // isn't forgotten once the `KeyedLock` has been reclaimed and
+        // recreated.
+        let locker = Arc::new(SyncLocker::new());
+        let locker_clone = locker.clone();
+        let result = thread::spawn(move || {
+            let mutex = locker_clone.get_mutex(1);
+            let _guard = mutex.lock().unwrap();
+            panic!("boom");
+        })
+        .join();
+        assert!(result.is_err());
+
+        assert!(locker.get_mutex(1).lock().is_err());
+    }
 }
@@ -0,0 +1,142 @@
+//! Lock-order cycle detection, enabled by the `deadlock-detection` feature.
+//!
+//! Every call to [`before_lock`] records, for the calling thread, an edge from
+//! each lock it already holds to the lock it is about to acquire (no edges are
+//! recorded when the thread already holds that same lock). If that new
+//! edge would close a cycle in the global lock-order graph, a latent deadlock
+//! exists -- two call paths take the same two locks in opposite orders -- and
+//! we panic immediately instead of waiting for the timing-dependent deadlock
+//! to actually happen at runtime.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+#[cfg(feature = "backtrace")]
+use backtrace::Backtrace;
+
+struct HeldLock {
+    id: String,
+    #[cfg(feature = "backtrace")]
+    backtrace: Backtrace,
+}
+
+thread_local! {
+    static HELD: RefCell<Vec<HeldLock>> = const { RefCell::new(Vec::new()) };
+}
+
+fn graph() -> &'static Mutex<HashMap<String, HashSet<String>>> {
+    static GRAPH: OnceLock<Mutex<HashMap<String, HashSet<String>>>> = OnceLock::new();
+    GRAPH.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Returns `true` if there is already a path `from -> ... -> to` in `graph`.
+fn reaches(graph: &HashMap<String, HashSet<String>>, from: &str, to: &str) -> bool {
+    let mut stack = vec![from.to_string()];
+    let mut visited = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        if !visited.insert(node.clone()) {
+            continue;
+        }
+        if let Some(neighbors) = graph.get(&node) {
+            stack.extend(neighbors.iter().cloned());
+        }
+    }
+    false
+}
+
+/// Call immediately before blocking to acquire a keyed lock identified by
+/// `id`. Returns a guard that must be held for as long as the lock is held;
+/// dropping it pops `id` off this thread's held-lock stack.
+///
+/// Panics if acquiring `id` while already holding the locks on this thread's
+/// stack would close a cycle in the lock-order graph.
+pub fn before_lock(id: &str) -> LockOrderGuard {
+    HELD.with(|held| {
+        let held_locks = held.borrow();
+        let already_held = held_locks.iter().any(|lock| lock.id == id);
+        if !already_held {
+            // A prior `before_lock` call may have panicked (on a detected
+            // cycle) while still holding this lock, poisoning it. The graph
+            // is just best-effort lock-order bookkeeping, not user data, so
+            // recovering and carrying on is preferable to panicking on every
+            // later call once one thread has hit a real deadlock report.
+            let mut graph = graph().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for lock in held_locks.iter() {
+                if reaches(&graph, id, &lock.id) {
+                    panic!("{}", cycle_message(&held_locks, id));
+                }
+                graph.entry(lock.id.clone()).or_default().insert(id.to_string());
+            }
+        }
+    });
+
+    HELD.with(|held| {
+        held.borrow_mut().push(HeldLock {
+            id: id.to_string(),
+            #[cfg(feature = "backtrace")]
+            backtrace: Backtrace::new(),
+        });
+    });
+
+    LockOrderGuard
+}
+
+#[cfg(feature = "backtrace")]
+fn cycle_message(held: &[HeldLock], new_id: &str) -> String {
+    let mut message = format!(
+        "deadlock detected: acquiring lock `{}` would close a lock-order cycle with locks already held on this thread:\n",
+        new_id
+    );
+    for lock in held {
+        message.push_str(&format!("  held `{}` taken at:\n{:?}\n", lock.id, lock.backtrace));
+    }
+    message
+}
+
+#[cfg(not(feature = "backtrace"))]
+fn cycle_message(held: &[HeldLock], new_id: &str) -> String {
+    let chain: Vec<&str> = held.iter().map(|lock| lock.id.as_str()).collect();
+    format!(
+        "deadlock detected: acquiring lock `{}` would close a lock-order cycle with locks already held on this thread: {:?} (enable the `backtrace` feature for lock-site backtraces)",
+        new_id, chain
+    )
+}
+
+/// Pops the associated lock off this thread's held-lock stack on drop.
+pub struct LockOrderGuard;
+
+impl Drop for LockOrderGuard {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            held.borrow_mut().pop();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::before_lock;
+
+    #[test]
+    fn test_no_panic_for_consistent_order() {
+        let _a = before_lock("a");
+        let _b = before_lock("b");
+    }
+
+    #[test]
+    #[should_panic(expected = "deadlock detected")]
+    fn test_panics_on_lock_order_cycle() {
+        {
+            let _a = before_lock("cycle-a");
+            let _b = before_lock("cycle-b");
+        }
+        {
+            let _b = before_lock("cycle-b");
+            let _a = before_lock("cycle-a");
+        }
+    }
+}
@@ -1,15 +1,100 @@
+pub mod blocking;
+#[cfg(feature = "deadlock-detection")]
+pub mod deadlock;
+pub mod non_blocking;
+mod owned_guard;
+pub mod priority_blocking;
+pub mod priority_non_blocking;
+pub mod rw_blocking;
+pub mod rw_non_blocking;
+
+pub use blocking::SyncLocker;
+pub use non_blocking::AsyncLocker;
+pub use priority_blocking::PriorityLocker;
+pub use priority_non_blocking::AsyncPriorityLocker;
+pub use rw_blocking::RwLocker;
+pub use rw_non_blocking::AsyncRwLocker;
+
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+/// Error returned by [`Locker::lock_mutex`], distinguishing a poisoned
+/// keyed `Mutex` from an error produced by the protected closure itself.
+#[derive(Debug)]
+pub enum LockError<E> {
+    /// A previous holder of this name's `Mutex` panicked while holding it.
+    Poisoned,
+    /// `code` returned an error.
+    Code(E),
+}
+
+impl<E: fmt::Display> fmt::Display for LockError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Poisoned => write!(f, "mutex poisoned by a panicking holder"),
+            LockError::Code(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for LockError<E> {}
+
+/// Error returned by [`Locker::try_lock_mutex`].
+#[derive(Debug)]
+pub enum TryLockError<E> {
+    /// A previous holder of this name's `Mutex` panicked while holding it.
+    Poisoned,
+    /// The `Mutex` is currently held by someone else.
+    WouldBlock,
+    /// `code` returned an error.
+    Code(E),
+}
+
+impl<E: fmt::Display> fmt::Display for TryLockError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned => write!(f, "mutex poisoned by a panicking holder"),
+            TryLockError::WouldBlock => write!(f, "mutex is currently locked"),
+            TryLockError::Code(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for TryLockError<E> {}
+
+/// A named `Mutex` entry together with whether a previous holder panicked
+/// while it was locked. `poisoned` lives here rather than on the `Mutex`
+/// itself so it survives eviction: once every `Arc` for a name is dropped,
+/// [`Locker::get_mutex`] hands out a fresh `Mutex` for that name, but the
+/// name's poisoning should not be forgotten along with it.
+struct MutexEntry {
+    mutex: Weak<Mutex<()>>,
+    poisoned: bool,
+}
 
 /// Named `Mutex` handler
 pub struct Locker {
-    locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    locks: RwLock<HashMap<String, MutexEntry>>,
 }
 
 impl Locker {
     pub fn new() -> Self {
         Locker {
-            locks: Mutex::new(HashMap::new()),
+            locks: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn is_poisoned(&self, name: &str) -> bool {
+        let locks = self.locks.read().unwrap();
+        locks.get(name).map_or(false, |entry| entry.poisoned)
+    }
+
+    fn mark_poisoned(&self, name: &str) {
+        let mut locks = self.locks.write().unwrap();
+        if let Some(entry) = locks.get_mut(name) {
+            entry.poisoned = true;
         }
     }
 
@@ -19,6 +104,10 @@ impl Locker {
     ///
     /// Then that `Mutex` can be used for locking thread.
     ///
+    /// Only a `Weak` reference to the `Mutex` is kept in `Locker`'s state, so once
+    /// every `Arc` returned for a name is dropped the entry becomes dead and its
+    /// memory can be reclaimed with [`Locker::retain_live`].
+    ///
     /// # Examples
     ///
     /// ```
@@ -29,37 +118,150 @@ impl Locker {
     /// let locker_clone = locker.clone();
     /// let name = "name";
     /// let mutex = locker.get_mutex(name); // locks
-    /// let _ = mutex.lock().unwrap();
+    /// let guard = mutex.lock().unwrap();
     /// std::thread::spawn(move || {
     ///     let mutex = locker.get_mutex(name);
-    ///     let _ = mutex.lock().unwrap(); // wait
+    ///     let _guard = mutex.lock().unwrap(); // wait
     /// });
-    /// // unlocks first lock
+    /// drop(guard); // unlocks first lock
     /// ```
     pub fn get_mutex<N>(&self, name: N) -> Arc<Mutex<()>>
     where
         N: Into<String>,
     {
-        let mut locks = self.locks.lock().unwrap();
-        let mutex = Arc::new(Mutex::new(()));
-        locks.entry(name.into()).or_insert(mutex).clone()
+        let name = name.into();
+        {
+            let locks = self.locks.read().unwrap();
+            if let Some(mutex) = locks.get(&name).and_then(|entry| entry.mutex.upgrade()) {
+                return mutex;
+            }
+        }
+        let mut locks = self.locks.write().unwrap();
+        match locks.entry(name) {
+            Entry::Occupied(mut entry) => entry.get().mutex.upgrade().unwrap_or_else(|| {
+                let mutex = Arc::new(Mutex::new(()));
+                entry.get_mut().mutex = Arc::downgrade(&mutex);
+                mutex
+            }),
+            Entry::Vacant(entry) => {
+                let mutex = Arc::new(Mutex::new(()));
+                entry.insert(MutexEntry {
+                    mutex: Arc::downgrade(&mutex),
+                    poisoned: false,
+                });
+                mutex
+            }
+        }
+    }
+
+    /// Drops entries whose `Mutex` no longer has any `Arc` holders, shrinking
+    /// the underlying map back down to the set of currently active names.
+    /// This also forgets those names' poisoning, same as dropping a
+    /// `std::sync::Mutex` forgets whether it was poisoned.
+    pub fn retain_live(&self) {
+        let mut locks = self.locks.write().unwrap();
+        locks.retain(|_, entry| entry.mutex.strong_count() > 0);
+    }
+
+    /// Number of names whose `Mutex` is still alive.
+    pub fn len(&self) -> usize {
+        let locks = self.locks.read().unwrap();
+        locks.values().filter(|entry| entry.mutex.strong_count() > 0).count()
+    }
+
+    /// Returns `true` if no name currently has a live `Mutex`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn lock_mutex<N, F, T, E>(&self, name: N, code: F) -> Result<T, E>
+    /// Runs `code` while holding `name`'s `Mutex`.
+    ///
+    /// The guard is held for the full duration of `code`, so the critical
+    /// section it protects is actually exclusive. Following std's poisoning
+    /// strategy, a panic while `code` runs poisons `name`; every later call
+    /// for that name then returns [`LockError::Poisoned`] instead of
+    /// silently proceeding, keeping that failure distinct from an `Err`
+    /// returned by `code` itself. Unlike `std::sync::Mutex`'s own poisoning,
+    /// this is tracked per name independently of the `Mutex`'s lifetime, so
+    /// it's still observed even after every `Arc` for that name is dropped
+    /// and [`Locker::get_mutex`] has handed out a fresh, otherwise-unpoisoned
+    /// `Mutex` for it.
+    pub fn lock_mutex<N, F, T, E>(&self, name: N, code: F) -> Result<T, LockError<E>>
     where
         N: Into<String>,
         F: FnOnce() -> Result<T, E>,
-        E: std::error::Error,
     {
-        let mutex = self.get_mutex(name);
-        let _ = mutex.lock().unwrap();
-        code()
+        let name = name.into();
+        if self.is_poisoned(&name) {
+            return Err(LockError::Poisoned);
+        }
+        let mutex = self.get_mutex(name.clone());
+        #[cfg(feature = "deadlock-detection")]
+        let _lock_order_guard = crate::deadlock::before_lock(&name);
+        match mutex.lock() {
+            Ok(_guard) => {
+                let _poison_on_panic = PoisonOnPanic { locker: self, name: &name };
+                code().map_err(LockError::Code)
+            }
+            Err(_poisoned) => {
+                self.mark_poisoned(&name);
+                Err(LockError::Poisoned)
+            }
+        }
+    }
+
+    /// Like [`Locker::lock_mutex`], but returns [`TryLockError::WouldBlock`]
+    /// immediately instead of blocking if `name`'s `Mutex` is already held.
+    pub fn try_lock_mutex<N, F, T, E>(&self, name: N, code: F) -> Result<T, TryLockError<E>>
+    where
+        N: Into<String>,
+        F: FnOnce() -> Result<T, E>,
+    {
+        let name = name.into();
+        if self.is_poisoned(&name) {
+            return Err(TryLockError::Poisoned);
+        }
+        let mutex = self.get_mutex(name.clone());
+        match mutex.try_lock() {
+            Ok(_guard) => {
+                let _poison_on_panic = PoisonOnPanic { locker: self, name: &name };
+                code().map_err(TryLockError::Code)
+            }
+            Err(std::sync::TryLockError::Poisoned(_)) => {
+                self.mark_poisoned(&name);
+                Err(TryLockError::Poisoned)
+            }
+            Err(std::sync::TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+}
+
+/// Marks `name` poisoned in `locker` if dropped while unwinding from a
+/// panic, same as a `std::sync::MutexGuard` poisons its `Mutex`. Scoped to
+/// the lifetime of the `Mutex` guard held alongside it in
+/// [`Locker::lock_mutex`]/[`Locker::try_lock_mutex`].
+struct PoisonOnPanic<'a> {
+    locker: &'a Locker,
+    name: &'a str,
+}
+
+impl Drop for PoisonOnPanic<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.locker.mark_poisoned(self.name);
+        }
+    }
+}
+
+impl Default for Locker {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Locker;
+    use super::{LockError, Locker, TryLockError};
     use std::sync::Arc;
 
     #[test]
@@ -106,15 +308,75 @@ mod tests {
     }
 
     #[test]
-    fn test_lock_mutex() -> Result<(), std::io::Error> {
+    fn test_lock_mutex() -> Result<(), Box<dyn std::error::Error>> {
         let value = String::from("value");
         let locker = Arc::new(Locker::new());
-        locker.lock_mutex("name", || {
+        locker.lock_mutex("name", || -> Result<(), std::io::Error> {
             println!("thread mutex locked");
             std::thread::sleep(std::time::Duration::from_secs(2));
             println!("thread mutex unlocked");
             println!("{}", value);
             Ok(())
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_mutex_propagates_poison() {
+        // No `_keepalive` `Arc` is held here: the panicking call below drops
+        // its own `Arc` as soon as it returns, so this also proves that
+        // poisoning isn't forgotten once the named `Mutex` has been
+        // reclaimed and recreated.
+        let locker = Arc::new(Locker::new());
+        let locker_clone = locker.clone();
+        let result = std::thread::spawn(move || {
+            let _ = locker_clone.lock_mutex("poison", || -> Result<(), std::io::Error> {
+                panic!("boom");
+            });
         })
+        .join();
+        assert!(result.is_err());
+
+        match locker.lock_mutex("poison", || -> Result<(), std::io::Error> { Ok(()) }) {
+            Err(LockError::Poisoned) => {}
+            other => panic!("expected Poisoned, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_try_lock_mutex_would_block() {
+        let locker = Arc::new(Locker::new());
+        let mutex = locker.get_mutex("name");
+        let _guard = mutex.lock().unwrap();
+
+        match locker.try_lock_mutex("name", || -> Result<(), std::io::Error> { Ok(()) }) {
+            Err(TryLockError::WouldBlock) => {}
+            other => panic!("expected WouldBlock, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_get_mutex_returns_same_instance_while_alive() {
+        let locker = Locker::new();
+        let mutex = locker.get_mutex("name");
+        let other = locker.get_mutex("name");
+        assert!(Arc::ptr_eq(&mutex, &other));
+    }
+
+    #[test]
+    fn test_map_shrinks_after_guards_are_dropped() {
+        let locker = Locker::new();
+        {
+            let _mutex = locker.get_mutex("name");
+            assert_eq!(locker.locks.read().unwrap().len(), 1);
+            assert_eq!(locker.len(), 1);
+        }
+        // The entry is dead now, but still occupies the map until pruned.
+        assert_eq!(locker.locks.read().unwrap().len(), 1);
+        assert_eq!(locker.len(), 0);
+
+        locker.retain_live();
+        assert_eq!(locker.locks.read().unwrap().len(), 0);
+        assert!(locker.is_empty());
     }
 }
@@ -1,19 +1,23 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
+use tokio::time::Elapsed;
 
 #[derive(Clone)]
 pub struct AsyncLocker<K, V = ()> {
     default_mutex_func: Arc<dyn Fn() -> V + Send + Sync + 'static>,
-    mutexes: Arc<RwLock<HashMap<K, Arc<Mutex<V>>>>>,
+    mutexes: Arc<RwLock<HashMap<K, Weak<Mutex<V>>>>>,
 }
 
 impl<K: Eq + Hash, V> AsyncLocker<K, V> {
     pub fn new(default_mutex_func: impl Fn() -> V + Send + Sync + 'static) -> Self {
         AsyncLocker {
             default_mutex_func: Arc::new(default_mutex_func),
-            mutexes: Arc::new(RwLock::new(HashMap::<K, Arc<Mutex<V>>>::new())),
+            mutexes: Arc::new(RwLock::new(HashMap::<K, Weak<Mutex<V>>>::new())),
         }
     }
 
@@ -21,6 +25,10 @@ impl<K: Eq + Hash, V> AsyncLocker<K, V> {
     ///
     /// Locks the current task until it is able to return `Mutex`.
     ///
+    /// Only a `Weak` reference to the `Mutex` is kept in the map, so once every
+    /// `Arc` returned for a key is dropped the entry becomes dead and its memory
+    /// can be reclaimed with [`AsyncLocker::retain_live`].
+    ///
     /// # Examples
     /// ```ignore
     /// use std::time::Duration;
@@ -42,20 +50,56 @@ impl<K: Eq + Hash, V> AsyncLocker<K, V> {
     pub async fn get_mutex(&self, key: K) -> Arc<Mutex<V>> {
         {
             let mutexes = self.mutexes.read().await;
-            let mutex_opt = mutexes.get(&key);
-            if let Some(mutex) = mutex_opt {
-                return mutex.clone();
-            };
+            if let Some(mutex) = mutexes.get(&key).and_then(Weak::upgrade) {
+                return mutex;
+            }
         }
         let mut mutexes = self.mutexes.write().await;
-        let new_mutex = Arc::new(Mutex::new((self.default_mutex_func)()));
-        mutexes.entry(key).or_insert(new_mutex).clone()
+        match mutexes.entry(key) {
+            Entry::Occupied(mut entry) => entry.get().upgrade().unwrap_or_else(|| {
+                let mutex = Arc::new(Mutex::new((self.default_mutex_func)()));
+                entry.insert(Arc::downgrade(&mutex));
+                mutex
+            }),
+            Entry::Vacant(entry) => {
+                let mutex = Arc::new(Mutex::new((self.default_mutex_func)()));
+                entry.insert(Arc::downgrade(&mutex));
+                mutex
+            }
+        }
+    }
+
+    /// Drops entries whose `Mutex` no longer has any `Arc` holders, shrinking
+    /// the underlying map back down to the set of currently active keys.
+    pub async fn retain_live(&self) {
+        let mut mutexes = self.mutexes.write().await;
+        mutexes.retain(|_, mutex| mutex.strong_count() > 0);
+    }
+
+    /// Number of keys whose `Mutex` is still alive.
+    pub async fn len(&self) -> usize {
+        let mutexes = self.mutexes.read().await;
+        mutexes.values().filter(|mutex| mutex.strong_count() > 0).count()
+    }
+
+    /// Returns `true` if no key currently has a live `Mutex`.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Waits for `key`'s `Mutex` to be acquired, bounding the wait to
+    /// `duration` instead of blocking the task indefinitely. Returns
+    /// `Err(Elapsed)` if the timeout fires before the lock is acquired.
+    pub async fn lock_timeout(&self, key: K, duration: Duration) -> Result<OwnedMutexGuard<V>, Elapsed> {
+        let mutex = self.get_mutex(key).await;
+        tokio::time::timeout(duration, mutex.lock_owned()).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::AsyncLocker;
+    use std::sync::Arc;
     use std::time::Duration;
     use tokio::time::delay_for;
 
@@ -106,4 +150,50 @@ mod tests {
 
         handle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_get_mutex_returns_same_instance_while_alive() {
+        let locker = AsyncLocker::<i32, &str>::new(|| "value");
+        let mutex = locker.get_mutex(1).await;
+        let other = locker.get_mutex(1).await;
+        assert!(Arc::ptr_eq(&mutex, &other));
+    }
+
+    #[tokio::test]
+    async fn test_map_shrinks_after_guards_are_dropped() {
+        let locker = AsyncLocker::<i32, &str>::new(|| "value");
+        {
+            let _mutex = locker.get_mutex(1).await;
+            assert_eq!(locker.mutexes.read().await.len(), 1);
+            assert_eq!(locker.len().await, 1);
+        }
+        assert_eq!(locker.mutexes.read().await.len(), 1);
+        assert_eq!(locker.len().await, 0);
+
+        locker.retain_live().await;
+        assert_eq!(locker.mutexes.read().await.len(), 0);
+        assert!(locker.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_lock_timeout_succeeds_once_free() {
+        let locker = Arc::new(AsyncLocker::<i32, &str>::new(|| "value"));
+        let locker_clone = locker.clone();
+        let held = locker.lock_timeout(1, Duration::from_secs(1)).await.unwrap();
+
+        let handle = tokio::spawn(async move {
+            locker_clone.lock_timeout(1, Duration::from_secs(1)).await.is_ok()
+        });
+
+        delay_for(Duration::from_millis(100)).await;
+        drop(held);
+        assert!(handle.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lock_timeout_errors_on_timeout() {
+        let locker = Arc::new(AsyncLocker::<i32, &str>::new(|| "value"));
+        let _held = locker.lock_timeout(1, Duration::from_secs(1)).await.unwrap();
+        assert!(locker.lock_timeout(1, Duration::from_millis(50)).await.is_err());
+    }
 }
@@ -0,0 +1,52 @@
+//! Support for handing out a lock guard that owns (rather than borrows) the
+//! `Arc` it was acquired through, so it can be returned from a function
+//! instead of tied to a local variable's lifetime.
+
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// A lock guard `G` bundled with the `Arc<A>` it was derived from.
+pub struct OwnedGuard<A, G> {
+    guard: ManuallyDrop<G>,
+    _owner: Arc<A>,
+}
+
+impl<A, G> OwnedGuard<A, G> {
+    /// Bundles `guard` together with the `Arc` it borrows from.
+    ///
+    /// # Safety
+    /// `guard` must have been obtained by locking `owner` (or a clone of the
+    /// same `Arc`), and its real lifetime must not be used beyond the point
+    /// where `owner`'s underlying value could be freed. This type upholds
+    /// that: it keeps `owner` alive for as long as it exists, and `Drop`
+    /// below drops `guard` before `owner` is dropped by the compiler's
+    /// automatic field drop glue.
+    pub unsafe fn new(guard: G, owner: Arc<A>) -> Self {
+        OwnedGuard {
+            guard: ManuallyDrop::new(guard),
+            _owner: owner,
+        }
+    }
+}
+
+impl<A, G: Deref> Deref for OwnedGuard<A, G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<A, G: DerefMut> DerefMut for OwnedGuard<A, G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<A, G> Drop for OwnedGuard<A, G> {
+    fn drop(&mut self) {
+        // Safety: nothing else touches `self.guard` after this point.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+    }
+}
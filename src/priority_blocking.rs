@@ -0,0 +1,243 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::{self, Thread};
+
+/// A single waiter parked on a [`KeyedLock`], ordered by priority and, for
+/// ties, by arrival order (lower `seq` wins, i.e. FIFO).
+struct Waiter {
+    priority: i64,
+    seq: u64,
+    granted: Arc<AtomicBool>,
+    thread: Thread,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Higher priority wins; on a tie the lower (earlier) seq wins, which
+        // we express by reversing the seq comparison for the max-heap.
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct LockState {
+    locked: bool,
+    waiters: BinaryHeap<Waiter>,
+}
+
+/// Per-key lock used by [`PriorityLocker`]. Grants access to the
+/// highest-priority waiter next, breaking ties FIFO.
+struct KeyedLock {
+    state: Mutex<LockState>,
+    seq: AtomicU64,
+}
+
+impl KeyedLock {
+    fn new() -> Self {
+        KeyedLock {
+            state: Mutex::new(LockState {
+                locked: false,
+                waiters: BinaryHeap::new(),
+            }),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(self: &Arc<Self>, priority: i64) -> PriorityGuard {
+        let mut state = self.state.lock().unwrap();
+        if !state.locked {
+            state.locked = true;
+            return PriorityGuard { lock: self.clone() };
+        }
+
+        let granted = Arc::new(AtomicBool::new(false));
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        state.waiters.push(Waiter {
+            priority,
+            seq,
+            granted: granted.clone(),
+            thread: thread::current(),
+        });
+        drop(state);
+
+        while !granted.load(Ordering::Acquire) {
+            thread::park();
+        }
+        PriorityGuard { lock: self.clone() }
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        match state.waiters.pop() {
+            // Hand the lock off directly to the next waiter; `locked` stays
+            // `true` so a freshly-arriving thread can't steal it in between.
+            Some(waiter) => {
+                waiter.granted.store(true, Ordering::Release);
+                waiter.thread.unpark();
+            }
+            None => state.locked = false,
+        }
+    }
+}
+
+/// Guard returned by [`PriorityLocker::lock`]. Releases and hands the lock to
+/// the next highest-priority waiter (if any) on drop.
+pub struct PriorityGuard {
+    lock: Arc<KeyedLock>,
+}
+
+impl Drop for PriorityGuard {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// Keyed lock where waiters are granted access in priority order rather than
+/// arrival order. Ties are broken FIFO, and a released lock hands off
+/// directly to the next queued waiter instead of letting a fresh arrival
+/// steal it.
+#[derive(Clone)]
+pub struct PriorityLocker<K> {
+    locks: Arc<RwLock<HashMap<K, Weak<KeyedLock>>>>,
+}
+
+impl<K: Eq + Hash> PriorityLocker<K> {
+    pub fn new() -> Self {
+        PriorityLocker {
+            locks: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn get_lock(&self, key: K) -> Arc<KeyedLock> {
+        {
+            let locks = self.locks.read().unwrap();
+            if let Some(lock) = locks.get(&key).and_then(Weak::upgrade) {
+                return lock;
+            }
+        }
+        let mut locks = self.locks.write().unwrap();
+        match locks.entry(key) {
+            Entry::Occupied(mut entry) => entry.get().upgrade().unwrap_or_else(|| {
+                let lock = Arc::new(KeyedLock::new());
+                entry.insert(Arc::downgrade(&lock));
+                lock
+            }),
+            Entry::Vacant(entry) => {
+                let lock = Arc::new(KeyedLock::new());
+                entry.insert(Arc::downgrade(&lock));
+                lock
+            }
+        }
+    }
+
+    /// Blocks the current thread until `key`'s lock is granted, giving
+    /// priority to the highest-`priority` waiter whenever the lock frees up.
+    pub fn lock(&self, key: K, priority: i64) -> PriorityGuard {
+        self.get_lock(key).lock(priority)
+    }
+
+    /// Drops entries whose lock no longer has any `Arc` holders.
+    pub fn retain_live(&self) {
+        let mut locks = self.locks.write().unwrap();
+        locks.retain(|_, lock| lock.strong_count() > 0);
+    }
+
+    /// Number of keys whose lock is still alive.
+    pub fn len(&self) -> usize {
+        let locks = self.locks.read().unwrap();
+        locks.values().filter(|lock| lock.strong_count() > 0).count()
+    }
+
+    /// Returns `true` if no key currently has a live lock.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Eq + Hash> Default for PriorityLocker<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityLocker;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_higher_priority_goes_first() {
+        let locker = Arc::new(PriorityLocker::new());
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // Hold the lock so the next two lockers queue up behind it.
+        let held = locker.lock("key", 0);
+
+        let mut handles = Vec::new();
+        for (priority, label) in [(1, "low"), (5, "high")] {
+            let locker = locker.clone();
+            let order = order.clone();
+            handles.push(thread::spawn(move || {
+                // Give both threads time to park before we release the holder.
+                thread::sleep(Duration::from_millis(50));
+                let _guard = locker.lock("key", priority);
+                order.lock().unwrap().push(label);
+            }));
+        }
+
+        thread::sleep(Duration::from_millis(150));
+        drop(held);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), vec!["high", "low"]);
+    }
+
+    #[test]
+    fn test_ties_broken_fifo() {
+        let locker = Arc::new(PriorityLocker::new());
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let held = locker.lock("key", 0);
+
+        let mut handles = Vec::new();
+        for label in ["first", "second"] {
+            let locker = locker.clone();
+            let order = order.clone();
+            handles.push(thread::spawn(move || {
+                thread::sleep(Duration::from_millis(if label == "first" { 20 } else { 70 }));
+                let _guard = locker.lock("key", 1);
+                order.lock().unwrap().push(label);
+            }));
+        }
+
+        thread::sleep(Duration::from_millis(150));
+        drop(held);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), vec!["first", "second"]);
+    }
+}
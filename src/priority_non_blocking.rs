@@ -0,0 +1,317 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+use tokio::sync::RwLock;
+
+/// A single task parked on an [`AsyncKeyedLock`], ordered by priority and,
+/// for ties, by arrival order (lower `seq` wins, i.e. FIFO).
+struct Waiter {
+    priority: i64,
+    seq: u64,
+    granted: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct LockState {
+    locked: bool,
+    waiters: BinaryHeap<Waiter>,
+}
+
+/// Per-key lock used by [`AsyncPriorityLocker`]. Grants access to the
+/// highest-priority waiter next, breaking ties FIFO.
+struct AsyncKeyedLock {
+    state: Mutex<LockState>,
+    seq: AtomicU64,
+}
+
+impl AsyncKeyedLock {
+    fn new() -> Self {
+        AsyncKeyedLock {
+            state: Mutex::new(LockState {
+                locked: false,
+                waiters: BinaryHeap::new(),
+            }),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.waiters.pop() {
+                // A cancelled waiter's `LockFuture` was dropped before it
+                // could be granted (see `LockFuture`'s `Drop` impl) -- skip
+                // it instead of handing the lock off to nobody.
+                Some(waiter) if waiter.cancelled.load(Ordering::Acquire) => continue,
+                // Hand the lock off directly to the next waiter; `locked`
+                // stays `true` so a freshly-arriving task can't steal it in
+                // between.
+                Some(waiter) => {
+                    waiter.granted.store(true, Ordering::Release);
+                    if let Some(waker) = waiter.waker.lock().unwrap().take() {
+                        waker.wake();
+                    }
+                    return;
+                }
+                None => {
+                    state.locked = false;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+enum FutureState {
+    NotStarted,
+    Waiting {
+        granted: Arc<AtomicBool>,
+        cancelled: Arc<AtomicBool>,
+        waker: Arc<Mutex<Option<Waker>>>,
+    },
+    /// A guard was handed out; it, not this future, now releases the lock.
+    Done,
+}
+
+/// Future returned by [`AsyncPriorityLocker::lock`].
+pub struct LockFuture {
+    lock: Arc<AsyncKeyedLock>,
+    priority: i64,
+    state: FutureState,
+}
+
+impl Future for LockFuture {
+    type Output = AsyncPriorityGuard;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match &this.state {
+            FutureState::NotStarted => {
+                let mut state = this.lock.state.lock().unwrap();
+                if !state.locked {
+                    state.locked = true;
+                    return Poll::Ready(AsyncPriorityGuard { lock: this.lock.clone() });
+                }
+
+                let granted = Arc::new(AtomicBool::new(false));
+                let cancelled = Arc::new(AtomicBool::new(false));
+                let waker = Arc::new(Mutex::new(Some(cx.waker().clone())));
+                let seq = this.lock.seq.fetch_add(1, Ordering::Relaxed);
+                state.waiters.push(Waiter {
+                    priority: this.priority,
+                    seq,
+                    granted: granted.clone(),
+                    cancelled: cancelled.clone(),
+                    waker: waker.clone(),
+                });
+                this.state = FutureState::Waiting { granted, cancelled, waker };
+                Poll::Pending
+            }
+            FutureState::Waiting { granted, waker, .. } => {
+                if granted.load(Ordering::Acquire) {
+                    this.state = FutureState::Done;
+                    Poll::Ready(AsyncPriorityGuard { lock: this.lock.clone() })
+                } else {
+                    *waker.lock().unwrap() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+            FutureState::Done => panic!("LockFuture polled after completion"),
+        }
+    }
+}
+
+impl Drop for LockFuture {
+    fn drop(&mut self) {
+        // If we're dropped (e.g. cancelled via `tokio::time::timeout`,
+        // `select!`, or task abort) while still queued, the `Waiter` we
+        // pushed in `poll` is otherwise left dangling in the heap forever:
+        // `unlock` would eventually pop it, flip `granted`, and consider the
+        // lock handed off, but nothing will ever poll this future again to
+        // turn that into an `AsyncPriorityGuard` that could release it.
+        if let FutureState::Waiting { granted, cancelled, .. } = &self.state {
+            // Reading and reacting to `granted` under `lock`'s own mutex
+            // keeps this in lock-step with `unlock`'s pop-and-grant, so we
+            // can't miss a grant that raced with our own cancellation.
+            let state = self.lock.state.lock().unwrap();
+            let already_granted = granted.load(Ordering::Acquire);
+            if !already_granted {
+                cancelled.store(true, Ordering::Release);
+            }
+            drop(state);
+
+            if already_granted {
+                // We were granted the lock but never produced a guard for
+                // it -- hand it off to the next waiter ourselves.
+                self.lock.unlock();
+            }
+        }
+    }
+}
+
+/// Guard returned by [`AsyncPriorityLocker::lock`]. Releases and hands the
+/// lock to the next highest-priority waiter (if any) on drop.
+pub struct AsyncPriorityGuard {
+    lock: Arc<AsyncKeyedLock>,
+}
+
+impl Drop for AsyncPriorityGuard {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// Tokio-based, priority-ordered keyed locker mirroring [`crate::AsyncLocker`].
+#[derive(Clone)]
+pub struct AsyncPriorityLocker<K> {
+    locks: Arc<RwLock<HashMap<K, Weak<AsyncKeyedLock>>>>,
+}
+
+impl<K: Eq + Hash> AsyncPriorityLocker<K> {
+    pub fn new() -> Self {
+        AsyncPriorityLocker {
+            locks: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    async fn get_lock(&self, key: K) -> Arc<AsyncKeyedLock> {
+        {
+            let locks = self.locks.read().await;
+            if let Some(lock) = locks.get(&key).and_then(Weak::upgrade) {
+                return lock;
+            }
+        }
+        let mut locks = self.locks.write().await;
+        match locks.entry(key) {
+            Entry::Occupied(mut entry) => entry.get().upgrade().unwrap_or_else(|| {
+                let lock = Arc::new(AsyncKeyedLock::new());
+                entry.insert(Arc::downgrade(&lock));
+                lock
+            }),
+            Entry::Vacant(entry) => {
+                let lock = Arc::new(AsyncKeyedLock::new());
+                entry.insert(Arc::downgrade(&lock));
+                lock
+            }
+        }
+    }
+
+    /// Waits until `key`'s lock is granted, giving priority to the
+    /// highest-`priority` waiter whenever the lock frees up.
+    pub async fn lock(&self, key: K, priority: i64) -> AsyncPriorityGuard {
+        let lock = self.get_lock(key).await;
+        LockFuture {
+            lock,
+            priority,
+            state: FutureState::NotStarted,
+        }
+        .await
+    }
+
+    /// Drops entries whose lock no longer has any `Arc` holders.
+    pub async fn retain_live(&self) {
+        let mut locks = self.locks.write().await;
+        locks.retain(|_, lock| lock.strong_count() > 0);
+    }
+
+    /// Number of keys whose lock is still alive.
+    pub async fn len(&self) -> usize {
+        let locks = self.locks.read().await;
+        locks.values().filter(|lock| lock.strong_count() > 0).count()
+    }
+
+    /// Returns `true` if no key currently has a live lock.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+impl<K: Eq + Hash> Default for AsyncPriorityLocker<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncPriorityLocker;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time::delay_for;
+
+    #[tokio::test]
+    async fn test_higher_priority_goes_first() {
+        let locker = Arc::new(AsyncPriorityLocker::new());
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let held = locker.lock(1, 0).await;
+
+        let mut handles = Vec::new();
+        for (priority, label) in [(1, "low"), (5, "high")] {
+            let locker = locker.clone();
+            let order = order.clone();
+            handles.push(tokio::spawn(async move {
+                delay_for(Duration::from_millis(50)).await;
+                let _guard = locker.lock(1, priority).await;
+                order.lock().unwrap().push(label);
+            }));
+        }
+
+        delay_for(Duration::from_millis(150)).await;
+        drop(held);
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), vec!["high", "low"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_does_not_leak_the_lock() {
+        let locker = Arc::new(AsyncPriorityLocker::new());
+        let held = locker.lock(1, 0).await;
+
+        // Queue a second waiter, then cancel it by dropping its `LockFuture`
+        // before it's ever granted.
+        let locker_clone = locker.clone();
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), async move {
+            locker_clone.lock(1, 0).await;
+        })
+        .await;
+        assert!(cancelled.is_err());
+
+        drop(held);
+
+        // If the cancelled waiter had leaked the lock, this would hang.
+        let guard = tokio::time::timeout(Duration::from_millis(100), locker.lock(1, 0)).await;
+        assert!(guard.is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::owned_guard::OwnedGuard;
+
+/// Tokio-based keyed `RwLock`, allowing many concurrent readers of the same
+/// key while still giving writers exclusive access to it.
+#[derive(Clone)]
+pub struct AsyncRwLocker<K, V = ()> {
+    default_value_func: Arc<dyn Fn() -> V + Send + Sync + 'static>,
+    locks: Arc<RwLock<HashMap<K, Weak<RwLock<V>>>>>,
+}
+
+impl<K: Eq + Hash, V> AsyncRwLocker<K, V> {
+    pub fn new(default_value_func: impl Fn() -> V + Send + Sync + 'static) -> Self {
+        AsyncRwLocker {
+            default_value_func: Arc::new(default_value_func),
+            locks: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Return reference to existig `RwLock` or insert new one.
+    ///
+    /// Only a `Weak` reference to the `RwLock` is kept in the map, so once
+    /// every `Arc` returned for a key is dropped the entry becomes dead and
+    /// its memory can be reclaimed with [`AsyncRwLocker::retain_live`].
+    async fn get_lock(&self, key: K) -> Arc<RwLock<V>> {
+        {
+            let locks = self.locks.read().await;
+            if let Some(lock) = locks.get(&key).and_then(Weak::upgrade) {
+                return lock;
+            }
+        }
+        let mut locks = self.locks.write().await;
+        match locks.entry(key) {
+            Entry::Occupied(mut entry) => entry.get().upgrade().unwrap_or_else(|| {
+                let lock = Arc::new(RwLock::new((self.default_value_func)()));
+                entry.insert(Arc::downgrade(&lock));
+                lock
+            }),
+            Entry::Vacant(entry) => {
+                let lock = Arc::new(RwLock::new((self.default_value_func)()));
+                entry.insert(Arc::downgrade(&lock));
+                lock
+            }
+        }
+    }
+
+    /// Waits for a shared read lock on `key`'s value. Waits while a writer
+    /// currently holds `key`'s lock; any number of readers may hold it at
+    /// once.
+    pub async fn read(&self, key: K) -> ReadGuard<V>
+    where
+        V: 'static,
+    {
+        let lock = self.get_lock(key).await;
+        let guard = lock.read().await;
+        // Safety: `guard` borrows from `lock`, which `OwnedGuard` keeps alive
+        // for as long as the returned `ReadGuard` exists.
+        let guard: RwLockReadGuard<'static, V> = unsafe { std::mem::transmute(guard) };
+        unsafe { OwnedGuard::new(guard, lock) }
+    }
+
+    /// Waits for the exclusive write lock on `key`'s value. Waits while any
+    /// reader or writer currently holds `key`'s lock.
+    pub async fn write(&self, key: K) -> WriteGuard<V>
+    where
+        V: 'static,
+    {
+        let lock = self.get_lock(key).await;
+        let guard = lock.write().await;
+        // Safety: see `AsyncRwLocker::read`.
+        let guard: RwLockWriteGuard<'static, V> = unsafe { std::mem::transmute(guard) };
+        unsafe { OwnedGuard::new(guard, lock) }
+    }
+
+    /// Drops entries whose `RwLock` no longer has any `Arc` holders.
+    pub async fn retain_live(&self) {
+        let mut locks = self.locks.write().await;
+        locks.retain(|_, lock| lock.strong_count() > 0);
+    }
+
+    /// Number of keys whose `RwLock` is still alive.
+    pub async fn len(&self) -> usize {
+        let locks = self.locks.read().await;
+        locks.values().filter(|lock| lock.strong_count() > 0).count()
+    }
+
+    /// Returns `true` if no key currently has a live `RwLock`.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+/// Shared read guard returned by [`AsyncRwLocker::read`].
+pub type ReadGuard<V> = OwnedGuard<RwLock<V>, RwLockReadGuard<'static, V>>;
+
+/// Exclusive write guard returned by [`AsyncRwLocker::write`].
+pub type WriteGuard<V> = OwnedGuard<RwLock<V>, RwLockWriteGuard<'static, V>>;
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncRwLocker;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time::delay_for;
+
+    #[tokio::test]
+    async fn test_concurrent_readers_same_key() {
+        let locker = Arc::new(AsyncRwLocker::<i32, i32>::new(|| 0));
+        let _reader_a = locker.read(1).await;
+        let _reader_b = locker.read(1).await;
+    }
+
+    #[tokio::test]
+    async fn test_writer_blocks_behind_reader_on_same_key() {
+        let locker = Arc::new(AsyncRwLocker::<i32, i32>::new(|| 0));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let written = Arc::new(Mutex::new(0));
+
+        let reader = locker.read(1).await;
+
+        let locker_clone = locker.clone();
+        let order_clone = order.clone();
+        let written_clone = written.clone();
+        let handle = tokio::spawn(async move {
+            let mut writer = locker_clone.write(1).await;
+            *writer = 42;
+            *written_clone.lock().unwrap() = *writer;
+            order_clone.lock().unwrap().push("write");
+        });
+
+        delay_for(Duration::from_millis(100)).await;
+        order.lock().unwrap().push("read-still-held");
+        // `reader` was kept alive while the writer looked up key 1, so both
+        // share the same per-key `RwLock` instead of the writer getting a
+        // freshly created one; dropping it now lets the blocked writer in.
+        drop(reader);
+        handle.await.unwrap();
+
+        assert_eq!(*order.lock().unwrap(), vec!["read-still-held", "write"]);
+        assert_eq!(*written.lock().unwrap(), 42);
+    }
+}